@@ -1,6 +1,7 @@
 pub use crate::arch::paging::*;
 use bit_allocator::BitAlloc;
-use crate::consts::MEMORY_OFFSET;
+use crate::consts::{MEMORY_OFFSET, MAX_CPU_NUM};
+use crate::arch::cpu;
 use super::HEAP_ALLOCATOR;
 use rcore_memory::*;
 use rcore_memory::cow::CowExt;
@@ -10,6 +11,10 @@ use crate::sync::{SpinNoIrqLock, SpinNoIrq, MutexGuard};
 use lazy_static::*;
 use log::*;
 use linked_list_allocator::LockedHeap;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 #[cfg(not(feature = "no_mmu"))]
 pub type MemorySet = rcore_memory::memory_set::MemorySet<InactivePageTable0>;
@@ -17,15 +22,21 @@ pub type MemorySet = rcore_memory::memory_set::MemorySet<InactivePageTable0>;
 #[cfg(feature = "no_mmu")]
 pub type MemorySet = rcore_memory::no_mmu::MemorySet<NoMMUSupportImpl>;
 
-// x86_64 support up to 256M memory
+// The `BitAlloc` width below is still a hard ceiling on total addressable
+// memory, but which frames within it are actually usable is no longer
+// assumed at compile time: `init_frame_allocator` fills it in from
+// whatever the boot memory map reports, instead of the whole bitmap
+// being marked available by default.
+
+// x86_64: up to 256M of tracked memory.
 #[cfg(target_arch = "x86_64")]
 pub type FrameAlloc = bit_allocator::BitAlloc64K;
 
-// RISCV has 8M memory
+// RISCV: up to 8M of tracked memory.
 #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
 pub type FrameAlloc = bit_allocator::BitAlloc4K;
 
-// Raspberry Pi 3 has 1G memory
+// Raspberry Pi 3: up to 1G of tracked memory.
 #[cfg(target_arch = "aarch64")]
 pub type FrameAlloc = bit_allocator::BitAlloc1M;
 
@@ -33,6 +44,83 @@ lazy_static! {
     pub static ref FRAME_ALLOCATOR: SpinNoIrqLock<FrameAlloc> = SpinNoIrqLock::new(FrameAlloc::default());
 }
 
+/// Total number of frames marked available by `init_frame_allocator`, and
+/// how many of them are still free. Tracked here since `BitAlloc` itself
+/// doesn't expose a count.
+static TOTAL_FRAMES: AtomicUsize = AtomicUsize::new(0);
+static FREE_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// One region of memory as reported by the bootloader or firmware memory
+/// map, **after** arch boot code has converted it to the same
+/// `MEMORY_OFFSET`-relative address space as the `target` addresses
+/// `alloc_frame`/`dealloc_frame` hand out — not a raw physical address.
+/// `init_frame_allocator` rejects any region whose `base` is below
+/// `MEMORY_OFFSET`, since that can only mean the conversion was skipped.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryRegion {
+    pub base: usize,
+    pub length: usize,
+    pub region_type: MemoryRegionType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryRegionType {
+    /// Free RAM the kernel may hand out as frames.
+    Usable,
+    /// Anything else: holes, MMIO, ACPI tables, reserved firmware memory.
+    Reserved,
+}
+
+extern "C" {
+    /// Provided by the linker script: the first physical address after
+    /// the kernel image (and the BSS-resident kernel heap within it).
+    fn end();
+}
+
+/// Initialize `FRAME_ALLOCATOR` from a boot memory map: mark every frame
+/// inside a `Usable` region available, except for the range still
+/// occupied by the kernel image itself. Must be called once from arch
+/// boot code before any other use of the frame allocator.
+pub fn init_frame_allocator(map: &[MemoryRegion]) {
+    // `end` lives in the same MEMORY_OFFSET-relative address space as
+    // `region.base` (both are kernel-virtual, not raw physical), so no
+    // conversion is needed to compare them.
+    let kernel_end = end as usize;
+    let mut ba = FRAME_ALLOCATOR.lock();
+    let mut total = 0;
+    for region in map.iter().filter(|r| r.region_type == MemoryRegionType::Usable) {
+        // `base` is documented as MEMORY_OFFSET-relative; a caller handing
+        // us a raw physical base below MEMORY_OFFSET would otherwise
+        // underflow the subtraction below into a bogus, huge frame range.
+        // Reject it outright instead of silently wrapping.
+        if region.base < MEMORY_OFFSET {
+            warn!(
+                "skipping memory region at {:#x}: below MEMORY_OFFSET ({:#x}), so it can't be a MEMORY_OFFSET-relative base",
+                region.base, MEMORY_OFFSET
+            );
+            continue;
+        }
+        let start = region.base.max(kernel_end);
+        let limit = region.base + region.length;
+        if start >= limit {
+            continue;
+        }
+        // Bitmap indices are physical frame numbers: subtract
+        // MEMORY_OFFSET here, matching the `(target - MEMORY_OFFSET) /
+        // PAGE_SIZE` convention `dealloc_frame` uses.
+        let frame_start = (start - MEMORY_OFFSET + PAGE_SIZE - 1) / PAGE_SIZE;
+        let frame_end = (limit - MEMORY_OFFSET) / PAGE_SIZE;
+        if frame_end > frame_start {
+            ba.insert(frame_start..frame_end);
+            total += frame_end - frame_start;
+        }
+    }
+    drop(ba);
+    TOTAL_FRAMES.store(total, Ordering::Relaxed);
+    FREE_FRAMES.store(total, Ordering::Relaxed);
+    info!("frame allocator: {} frames ({} MiB) available", total, total * PAGE_SIZE / 1024 / 1024);
+}
+
 /// The only way to get active page table
 ///
 /// ## CHANGE LOG
@@ -52,21 +140,440 @@ pub fn active_table() -> ActivePageTable {
 }
 
 
+/// A cache that can give back frames on request, e.g. a page cache, COW
+/// snapshot store, or inode buffer pool. Subsystems register one at init
+/// so the frame allocator can reclaim clean, droppable memory before
+/// resorting to swap.
+pub trait Shrinker: Send + Sync {
+    /// Number of frames this cache currently holds.
+    fn count(&self) -> usize;
+    /// Try to free frames until the cache holds `target` or fewer.
+    /// Returns the number actually freed.
+    fn reclaim(&self, target: usize) -> usize;
+}
+
+lazy_static! {
+    /// Shrinkers registered by subsystems with droppable caches, consulted
+    /// in registration order before falling back to swap.
+    static ref SHRINKERS: SpinNoIrqLock<Vec<&'static dyn Shrinker>> = SpinNoIrqLock::new(Vec::new());
+}
+
+/// Register a shrinker to be asked for frames when the allocator is under
+/// pressure. Typically called once at subsystem init (e.g. page cache or
+/// inode buffer pool init) with a `&'static` reference to a `lazy_static`
+/// cache; no subsystem in this tree does that yet, so until one does this
+/// reclaim path stays cold in practice (see the `shrink_and_alloc` test
+/// below for its exercised behavior).
+pub fn register_shrinker(shrinker: &'static dyn Shrinker) {
+    SHRINKERS.lock().push(shrinker);
+}
+
+/// Ask every registered shrinker to free frames, retrying `alloc` after
+/// each one makes progress. Returns as soon as a frame becomes available.
+fn shrink_and_alloc() -> Option<usize> {
+    for shrinker in SHRINKERS.lock().iter() {
+        let target = shrinker.count().saturating_sub(1);
+        if shrinker.reclaim(target) > 0 {
+            if let Some(ret) = raw_alloc_id() {
+                return Some(ret);
+            }
+        }
+    }
+    None
+}
+
+/// Take one frame straight from the global bitmap, keeping `FREE_FRAMES`
+/// in sync. Bypasses the per-CPU magazine; used by the slow paths
+/// (magazine drain, shrinker, swap) that already hold no other lock.
+fn raw_alloc_id() -> Option<usize> {
+    let id = FRAME_ALLOCATOR.lock().alloc();
+    if id.is_some() {
+        FREE_FRAMES.fetch_sub(1, Ordering::Relaxed);
+    }
+    id
+}
+
+/// Return one frame straight to the global bitmap, keeping `FREE_FRAMES`
+/// in sync.
+fn raw_dealloc_id(id: usize) {
+    FRAME_ALLOCATOR.lock().dealloc(id);
+    FREE_FRAMES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Pull up to `REFILL_BATCH` frames into `magazine`, taking the global
+/// lock once for the whole batch rather than once per frame.
+fn refill_magazine(magazine: &mut Magazine) {
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let mut taken = 0;
+    for _ in 0..REFILL_BATCH {
+        match allocator.alloc() {
+            Some(id) => {
+                magazine.push(id);
+                taken += 1;
+            }
+            None => break,
+        }
+    }
+    drop(allocator);
+    FREE_FRAMES.fetch_sub(taken, Ordering::Relaxed);
+}
+
+/// Push up to `REFILL_BATCH` frames out of `magazine` back to the global
+/// bitmap, taking the global lock once for the whole batch.
+fn flush_magazine(magazine: &mut Magazine) {
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let mut freed = 0;
+    for _ in 0..REFILL_BATCH {
+        match magazine.pop() {
+            Some(id) => {
+                allocator.dealloc(id);
+                freed += 1;
+            }
+            None => break,
+        }
+    }
+    drop(allocator);
+    FREE_FRAMES.fetch_add(freed, Ordering::Relaxed);
+}
+
+/// Per-CPU cache of free frames ("magazine"), so the common allocation
+/// path doesn't have to touch the global `FRAME_ALLOCATOR` lock. Refills
+/// and flushes move a whole `REFILL_BATCH` at once to keep the global
+/// lock's hit rate low.
+const MAGAZINE_CAPACITY: usize = 2 * REFILL_BATCH;
+const REFILL_BATCH: usize = 16;
+
+struct Magazine {
+    frames: [usize; MAGAZINE_CAPACITY],
+    len: usize,
+}
+
+impl Magazine {
+    const fn empty() -> Self {
+        Magazine { frames: [0; MAGAZINE_CAPACITY], len: 0 }
+    }
+    fn pop(&mut self) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(self.frames[self.len])
+    }
+    fn push(&mut self, id: usize) -> bool {
+        if self.len == MAGAZINE_CAPACITY {
+            return false;
+        }
+        self.frames[self.len] = id;
+        self.len += 1;
+        true
+    }
+}
+
+lazy_static! {
+    static ref MAGAZINES: Vec<SpinNoIrqLock<Magazine>> =
+        (0..MAX_CPU_NUM).map(|_| SpinNoIrqLock::new(Magazine::empty())).collect();
+}
+
+/// The calling core's magazine. `cpu::id()` is a dense index into
+/// `0..MAX_CPU_NUM` by contract everywhere else in the kernel.
+fn local_magazine() -> &'static SpinNoIrqLock<Magazine> {
+    debug_assert!(cpu::id() < MAX_CPU_NUM, "cpu::id() out of range for MAGAZINES");
+    &MAGAZINES[cpu::id()]
+}
+
+/// Drain every per-CPU magazine back into the global allocator. Called
+/// under memory pressure so frames parked idle in another core's cache
+/// aren't invisible to the shrinker/swap reclaim path. Returns the number
+/// of frames freed.
+pub fn drain_magazines() -> usize {
+    let mut freed = 0;
+    for magazine in MAGAZINES.iter() {
+        let mut mag = magazine.lock();
+        while let Some(id) = mag.pop() {
+            raw_dealloc_id(id);
+            freed += 1;
+        }
+    }
+    freed
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct GlobalFrameAlloc;
 
 impl FrameAllocator for GlobalFrameAlloc {
     fn alloc(&self) -> Option<usize> {
-        // get the real address of the alloc frame
-        let ret = FRAME_ALLOCATOR.lock().alloc().map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
+        let mut magazine = local_magazine().lock();
+        if let Some(id) = magazine.pop() {
+            let ret = id * PAGE_SIZE + MEMORY_OFFSET;
+            trace!("Allocate frame (percpu): {:x}", ret);
+            return Some(ret);
+        }
+        // Local magazine is empty: refill a batch under a single global
+        // lock acquisition rather than taking the lock once per frame.
+        refill_magazine(&mut magazine);
+        let mut ret = magazine.pop();
+        drop(magazine);
+        if ret.is_none() {
+            // Still nothing: other cores may be sitting on idle frames.
+            if drain_magazines() > 0 {
+                ret = raw_alloc_id();
+            }
+        }
+        if ret.is_none() {
+            // Out of physical memory: first ask registered shrinkers to
+            // drop clean, reclaimable memory...
+            ret = shrink_and_alloc();
+        }
+        if ret.is_none() {
+            // ...and only then push anonymous user pages out to swap,
+            // one frame at a time, via the clock policy.
+            while ret.is_none() && swap_out_one() {
+                ret = raw_alloc_id();
+            }
+        }
+        let ret = ret.map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
         trace!("Allocate frame: {:x?}", ret);
         ret
-        // TODO: try to swap out when alloc failed
     }
     fn dealloc(&self, target: usize) {
         trace!("Deallocate frame: {:x}", target);
-        FRAME_ALLOCATOR.lock().dealloc((target - MEMORY_OFFSET) / PAGE_SIZE);
+        let id = (target - MEMORY_OFFSET) / PAGE_SIZE;
+        let mut magazine = local_magazine().lock();
+        if magazine.push(id) {
+            return;
+        }
+        // Local magazine is full: flush a batch back to the global
+        // allocator under a single lock acquisition, to make room rather
+        // than growing unbounded.
+        flush_magazine(&mut magazine);
+        magazine.push(id);
+    }
+}
+
+impl GlobalFrameAlloc {
+    /// Total number of frames made available by `init_frame_allocator`.
+    pub fn total_frames() -> usize {
+        TOTAL_FRAMES.load(Ordering::Relaxed)
+    }
+    /// Number of frames not currently handed out to any caller: free in
+    /// the global bitmap, plus whatever is idle in a per-CPU magazine.
+    pub fn free_frames() -> usize {
+        let cached: usize = MAGAZINES.iter().map(|m| m.lock().len).sum();
+        FREE_FRAMES.load(Ordering::Relaxed) + cached
+    }
+}
+
+/// A backing store that swapped-out pages are written to and read back
+/// from, keyed by an opaque id chosen by the device itself (e.g. a slot
+/// index on a ramdisk or block device).
+pub trait SwapDevice: Send + Sync {
+    /// Write one page of data to a free slot, returning its id.
+    fn write(&self, frame: &[u8]) -> usize;
+    /// Read the page previously stored under `id` back into `frame`.
+    fn read(&self, id: usize, frame: &mut [u8]);
+}
+
+lazy_static! {
+    static ref SWAP_DEVICE: SpinNoIrqLock<Option<Box<dyn SwapDevice>>> = SpinNoIrqLock::new(None);
+}
+
+/// Install the backing store used to hold evicted pages. Must be called
+/// from arch boot code, once a block device is available, before any
+/// swap-out can succeed; no boot path in this tree calls it yet, so
+/// `swap_out_one` stays inert until one does (see the test below for its
+/// exercised read/write contract).
+pub fn init_swap_device(device: Box<dyn SwapDevice>) {
+    *SWAP_DEVICE.lock() = Some(device);
+}
+
+/// A candidate for eviction: an anonymous, user-owned page mapped at
+/// `vaddr` in the address space identified by `token` (that process's
+/// page-table root). Tracking the owning table, not just a bare `vaddr`,
+/// is what lets the sweep resolve the right PTE even when some other
+/// process is the one currently scheduled.
+struct SwappableFrame {
+    token: usize,
+    vaddr: usize,
+}
+
+lazy_static! {
+    /// Frames eligible for the clock sweep. Subsystems that map anonymous
+    /// user memory register/unregister their pages here; kernel mappings
+    /// and in-flight DMA buffers are never added, so they can't be evicted.
+    static ref SWAPPABLE: SpinNoIrqLock<VecDeque<SwappableFrame>> = SpinNoIrqLock::new(VecDeque::new());
+
+    /// Address-space tokens the sweep is allowed to activate. A token has
+    /// to be registered here before any of its pages are handed to
+    /// `register_swappable`, and `retire_address_space` removes it again
+    /// at process teardown. `with_table`/`swap_out_one` refuse to
+    /// `set_token` to anything not in this set, so a `SWAPPABLE` entry
+    /// left behind by a missed `unregister_swappable` call can never
+    /// cause a dangling/freed page table to get loaded.
+    static ref LIVE_TOKENS: SpinNoIrqLock<BTreeSet<usize>> = SpinNoIrqLock::new(BTreeSet::new());
+}
+
+/// Rotating position of the clock hand over `SWAPPABLE`.
+static SWAP_CURSOR: AtomicUsize = AtomicUsize::new(0);
+
+/// Record that `token` identifies a live address space, allowing the
+/// clock sweep to activate it. Must be called once a process's page
+/// table is created (e.g. from process creation/`fork`), before any of
+/// its pages are passed to `register_swappable`.
+pub fn note_address_space_live(token: usize) {
+    LIVE_TOKENS.lock().insert(token);
+}
+
+/// Tear down all swap bookkeeping for `token`: evicts every page still
+/// tracked under it from `SWAPPABLE` and forgets the token, so the sweep
+/// can never activate it again. **Must** be called from process teardown
+/// (wherever the `InactivePageTable` for `token` is dropped), before that
+/// page table's memory is actually freed — this is what keeps a process
+/// exit from leaving stale `SWAPPABLE` entries pointing at freed memory.
+pub fn retire_address_space(token: usize) {
+    LIVE_TOKENS.lock().remove(&token);
+    SWAPPABLE.lock().retain(|f| f.token != token);
+}
+
+/// Mark `vaddr`, owned by the address space `token`, as an anonymous user
+/// page that may be swapped out under memory pressure. `token` must
+/// already have been registered with `note_address_space_live`.
+pub fn register_swappable(token: usize, vaddr: usize) {
+    SWAPPABLE.lock().push_back(SwappableFrame { token, vaddr });
+}
+
+/// Stop tracking `vaddr` in `token`'s address space, e.g. because the
+/// mapping is being torn down for good rather than merely swapped out.
+pub fn unregister_swappable(token: usize, vaddr: usize) {
+    SWAPPABLE.lock().retain(|f| !(f.token == token && f.vaddr == vaddr));
+}
+
+/// Run `f` against the page table identified by `token`: temporarily
+/// activate it if some other table is currently loaded, then restore
+/// whatever was active before returning. This is what lets the clock
+/// sweep touch a PTE belonging to a process that isn't scheduled right
+/// now, without disturbing the caller's own active table.
+///
+/// Panics rather than switching if `token` isn't in `LIVE_TOKENS`, since
+/// activating an unvalidated token could mean loading a freed page table.
+/// `swap_out_one` checks the token before calling; `swap_in` does not, so
+/// a fault in an address space never passed to `note_address_space_live`
+/// panics here.
+fn with_table<T>(token: usize, f: impl FnOnce(&mut ActivePageTable) -> T) -> T {
+    assert!(LIVE_TOKENS.lock().contains(&token), "refusing to activate a non-live page-table token");
+    let current = InactivePageTable0::active_token();
+    if current != token {
+        unsafe { InactivePageTable0::set_token(token); }
+    }
+    let ret = f(&mut active_table());
+    if current != token {
+        unsafe { InactivePageTable0::set_token(current); }
     }
+    ret
+}
+
+/// Outcome of inspecting one `SWAPPABLE` entry during a sweep step.
+enum SweepStep {
+    /// The mapping is gone; the tracking entry is stale.
+    Stale,
+    /// Recently accessed; reference bit cleared, try another candidate.
+    Skipped,
+    /// Evicted; the physical frame backing it is now free.
+    Evicted { frame: usize },
+}
+
+/// Run one step of the second-chance (clock) sweep: skip over recently
+/// accessed pages clearing their reference bit, and evict the first one
+/// found with a clear bit. Returns whether a frame was freed.
+fn swap_out_one() -> bool {
+    let mut list = SWAPPABLE.lock();
+    // At most two full laps: one to clear reference bits, one to evict.
+    let laps = list.len() * 2;
+    for _ in 0..laps {
+        // `list` shrinks as stale/evicted entries are removed below, so
+        // re-check on every iteration rather than trusting the lap count.
+        if list.is_empty() {
+            return false;
+        }
+        let idx = SWAP_CURSOR.fetch_add(1, Ordering::Relaxed) % list.len();
+        let token = list[idx].token;
+        let vaddr = list[idx].vaddr;
+
+        if !LIVE_TOKENS.lock().contains(&token) {
+            // The owning process was retired (or never registered) after
+            // this entry was queued; `retire_address_space` should have
+            // already removed it, but don't bet a dangling-CR3 load on
+            // that — drop it here too and never touch `token`.
+            list.remove(idx);
+            continue;
+        }
+
+        let step = with_table(token, |table| match table.get_entry(vaddr) {
+            None => SweepStep::Stale,
+            Some(entry) => {
+                if entry.accessed() {
+                    entry.clear_accessed();
+                    entry.update();
+                    return SweepStep::Skipped;
+                }
+                let frame = entry.target();
+                let mut buf = [0u8; PAGE_SIZE];
+                unsafe {
+                    buf.copy_from_slice(core::slice::from_raw_parts(vaddr as *const u8, PAGE_SIZE));
+                }
+                let id = SWAP_DEVICE.lock().as_ref().expect("no swap device installed").write(&buf);
+                // Present becomes false. `target` normally holds a
+                // page-aligned physical frame address, so scale the swap
+                // id by `PAGE_SIZE` before storing it there and divide it
+                // back out on read-back (see `swap_in`/`page_fault_handler`)
+                // instead of writing the raw id, which `set_target` would
+                // otherwise truncate to zero.
+                entry.set_target(id * PAGE_SIZE);
+                entry.set_present(false);
+                entry.set_swapped(true);
+                entry.update();
+                SweepStep::Evicted { frame }
+            }
+        });
+
+        match step {
+            SweepStep::Stale => {
+                // Mapping already gone in that address space; drop the
+                // stale entry and keep looking.
+                list.remove(idx);
+            }
+            SweepStep::Skipped => {}
+            SweepStep::Evicted { frame } => {
+                list.remove(idx);
+                drop(list);
+                // Free straight to the global bitmap (bypassing the
+                // per-CPU magazine) so the caller's immediate retry can
+                // see the frame.
+                raw_dealloc_id((frame - MEMORY_OFFSET) / PAGE_SIZE);
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Swap `vaddr` back in for the address space `token`: allocate a fresh
+/// frame, read its contents back from the swap device, and restore the
+/// mapping.
+fn swap_in(token: usize, vaddr: usize, id: usize) {
+    let frame = alloc_frame().expect("out of memory during swap-in");
+    let mut buf = [0u8; PAGE_SIZE];
+    SWAP_DEVICE.lock().as_ref().expect("no swap device installed").read(id, &mut buf);
+    unsafe {
+        core::slice::from_raw_parts_mut(frame as *mut u8, PAGE_SIZE).copy_from_slice(&buf);
+    }
+    with_table(token, |table| {
+        let entry = table.get_entry(vaddr).expect("swapped page has no PTE");
+        entry.set_target(frame);
+        entry.set_present(true);
+        entry.set_swapped(false);
+        entry.update();
+    });
+    register_swappable(token, vaddr);
 }
 
 pub fn alloc_frame() -> Option<usize> {
@@ -76,24 +583,129 @@ pub fn dealloc_frame(target: usize) {
     GlobalFrameAlloc.dealloc(target);
 }
 
+/// Allocate `2^order` physically contiguous frames aligned to
+/// `2^align_log2`, returning the address of the first one. Used for
+/// huge-page mappings and DMA buffers, which need more than one frame at
+/// a time and can't tolerate a scattered one-at-a-time allocation.
+pub fn alloc_frames(order: usize, align_log2: usize) -> Option<usize> {
+    GlobalFrameAlloc.alloc_contiguous(order, align_log2)
+}
+/// Free a run of `2^order` contiguous frames previously returned by
+/// `alloc_frames`.
+pub fn dealloc_frames(target: usize, order: usize) {
+    GlobalFrameAlloc.dealloc_contiguous(target, order);
+}
+
+impl GlobalFrameAlloc {
+    /// Find `2^order` consecutive free frames aligned to `2^align_log2`
+    /// and mark them all allocated.
+    pub fn alloc_contiguous(&self, order: usize, align_log2: usize) -> Option<usize> {
+        let mut id = FRAME_ALLOCATOR.lock().alloc_contiguous(1 << order, align_log2);
+        if id.is_none() {
+            // Frames parked in per-CPU magazines are still marked
+            // allocated in the global bitmap, so a run that's actually
+            // available can be missed. Drain them back, same as the
+            // single-frame slow path in `GlobalFrameAlloc::alloc` does,
+            // before reporting failure.
+            drain_magazines();
+            id = FRAME_ALLOCATOR.lock().alloc_contiguous(1 << order, align_log2);
+        }
+        let ret = id.map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
+        if ret.is_some() {
+            FREE_FRAMES.fetch_sub(1 << order, Ordering::Relaxed);
+        }
+        trace!("Allocate {} contiguous frames: {:x?}", 1 << order, ret);
+        ret
+    }
+    /// Free a run of `2^order` frames starting at `target`, all under a
+    /// single lock acquisition so the run is freed atomically.
+    pub fn dealloc_contiguous(&self, target: usize, order: usize) {
+        trace!("Deallocate {} contiguous frames: {:x}", 1 << order, target);
+        let start = (target - MEMORY_OFFSET) / PAGE_SIZE;
+        let mut ba = FRAME_ALLOCATOR.lock();
+        for frame in start..start + (1 << order) {
+            ba.dealloc(frame);
+        }
+        drop(ba);
+        FREE_FRAMES.fetch_add(1 << order, Ordering::Relaxed);
+    }
+}
+
 pub struct KernelStack(usize);
 const STACK_SIZE: usize = 0x8000;
+#[cfg(not(feature = "no_mmu"))]
+const GUARD_PAGE_SIZE: usize = PAGE_SIZE;
+#[cfg(feature = "no_mmu")]
+const GUARD_PAGE_SIZE: usize = 0;
 
 impl KernelStack {
     pub fn new() -> Self {
         use alloc::alloc::{alloc, Layout};
-        let bottom = unsafe{ alloc(Layout::from_size_align(STACK_SIZE, STACK_SIZE).unwrap()) } as usize;
+        // Reserve one extra page below the usable stack and unmap it, so an
+        // overflow takes an immediate page fault instead of silently
+        // corrupting whatever sits below. No-MMU targets have no paging to
+        // unmap with, so they keep the old bare allocation.
+        let bottom = unsafe {
+            alloc(Layout::from_size_align(GUARD_PAGE_SIZE + STACK_SIZE, STACK_SIZE).unwrap())
+        } as usize;
+        #[cfg(not(feature = "no_mmu"))]
+        Self::protect_guard_page(bottom);
         KernelStack(bottom)
     }
     pub fn top(&self) -> usize {
-        self.0 + STACK_SIZE
+        self.0 + GUARD_PAGE_SIZE + STACK_SIZE
+    }
+
+    /// Clear the present/writable bits on the guard page so any access to
+    /// it faults deterministically instead of corrupting adjacent memory.
+    ///
+    /// This assumes the kernel heap is mapped 4 KiB at a time here. If it
+    /// were ever backed by a huge-page leaf instead, clearing `present` on
+    /// it would unmap the whole huge page rather than just this one,
+    /// silently taking the live stack above it down too. We have no way
+    /// to query the mapping's page size through the `Entry` trait, so
+    /// this is checked with a hard runtime `assert!`, not a `debug_assert!`
+    /// — release kernels must not run with a silently corrupted stack any
+    /// more than debug ones should.
+    #[cfg(not(feature = "no_mmu"))]
+    fn protect_guard_page(bottom: usize) {
+        let mut table = active_table();
+        for page in (bottom..bottom + GUARD_PAGE_SIZE).step_by(PAGE_SIZE) {
+            if let Some(entry) = table.get_entry(page) {
+                entry.set_present(false);
+                entry.set_writable(false);
+                entry.update();
+            }
+        }
+        assert!(
+            table.get_entry(bottom + GUARD_PAGE_SIZE).map(|e| e.present()).unwrap_or(false),
+            "unmapping the guard page took the stack above it with it; is the kernel heap huge-page mapped?"
+        );
+    }
+
+    /// Restore the guard page's mapping before the underlying memory is
+    /// handed back to the allocator, which needs to write into it.
+    #[cfg(not(feature = "no_mmu"))]
+    fn unprotect_guard_page(bottom: usize) {
+        let mut table = active_table();
+        for page in (bottom..bottom + GUARD_PAGE_SIZE).step_by(PAGE_SIZE) {
+            if let Some(entry) = table.get_entry(page) {
+                entry.set_present(true);
+                entry.set_writable(true);
+                entry.update();
+            }
+        }
     }
 }
 
 impl Drop for KernelStack {
     fn drop(&mut self) {
         use alloc::alloc::{dealloc, Layout};
-        unsafe{ dealloc(self.0 as _, Layout::from_size_align(STACK_SIZE, STACK_SIZE).unwrap()); }
+        #[cfg(not(feature = "no_mmu"))]
+        Self::unprotect_guard_page(self.0);
+        unsafe {
+            dealloc(self.0 as _, Layout::from_size_align(GUARD_PAGE_SIZE + STACK_SIZE, STACK_SIZE).unwrap());
+        }
     }
 }
 
@@ -103,6 +715,13 @@ impl Drop for KernelStack {
 #[cfg(not(feature = "no_mmu"))]
 pub fn page_fault_handler(addr: usize) -> bool {
     info!("start handling swap in/out page fault, badva={:x}", addr);
+    let vaddr = addr & !(PAGE_SIZE - 1);
+    let token = InactivePageTable0::active_token();
+    let swapped_id = active_table().get_entry(vaddr).filter(|e| e.swapped() && !e.present()).map(|e| e.target() / PAGE_SIZE);
+    if let Some(id) = swapped_id {
+        swap_in(token, vaddr, id);
+        return true;
+    }
     process().memory_set.page_fault_handler(addr)
 }
 
@@ -130,3 +749,164 @@ impl rcore_memory::no_mmu::NoMMUSupport for NoMMUSupportImpl {
 pub fn page_fault_handler(_addr: usize) -> bool {
     unreachable!()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bit_allocator::{BitAlloc, BitAlloc64K};
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    // `GlobalFrameAlloc::{alloc,dealloc}_contiguous` touch
+    // `FRAME_ALLOCATOR`/`FREE_FRAMES` and, on a failed allocation, drain
+    // every per-CPU magazine, but never look up the calling core's own
+    // magazine, so unlike plain `alloc`/`dealloc` they're safe to exercise
+    // directly here without a running kernel behind `cpu::id()`. They do mutate
+    // that shared global state though, so tests that do so must hold
+    // `TEST_ALLOC_LOCK` for their whole body — otherwise the default
+    // multithreaded test harness runs them concurrently and they race on
+    // the same bitmap/counters.
+    lazy_static::lazy_static! {
+        static ref TEST_ALLOC_LOCK: SpinNoIrqLock<()> = SpinNoIrqLock::new(());
+    }
+
+    #[test]
+    fn alloc_contiguous_applies_memory_offset_and_order() {
+        let _guard = TEST_ALLOC_LOCK.lock();
+        FRAME_ALLOCATOR.lock().insert(0..16);
+        TOTAL_FRAMES.store(16, Ordering::Relaxed);
+        FREE_FRAMES.store(16, Ordering::Relaxed);
+
+        let base = GlobalFrameAlloc.alloc_contiguous(2, 0).unwrap(); // 2^2 = 4 frames
+        assert_eq!((base - MEMORY_OFFSET) % PAGE_SIZE, 0);
+        assert_eq!(GlobalFrameAlloc::free_frames(), 16 - 4);
+
+        GlobalFrameAlloc.dealloc_contiguous(base, 2);
+        assert_eq!(GlobalFrameAlloc::free_frames(), 16);
+    }
+
+    #[test]
+    fn dealloc_contiguous_frees_every_frame_in_the_run() {
+        let _guard = TEST_ALLOC_LOCK.lock();
+        FRAME_ALLOCATOR.lock().insert(0..16);
+        TOTAL_FRAMES.store(16, Ordering::Relaxed);
+        FREE_FRAMES.store(16, Ordering::Relaxed);
+
+        let base = GlobalFrameAlloc.alloc_contiguous(3, 0).unwrap(); // 8 frames
+        GlobalFrameAlloc.dealloc_contiguous(base, 3);
+        // The whole run should be available again as one block.
+        assert_eq!(GlobalFrameAlloc.alloc_contiguous(3, 0), Some(base));
+    }
+
+    /// A shrinker that "owns" a fixed set of frames and gives them back on
+    /// `reclaim`, standing in for a real page cache/inode buffer pool.
+    struct TestShrinker {
+        frames: SpinNoIrqLock<Vec<usize>>,
+    }
+
+    impl Shrinker for TestShrinker {
+        fn count(&self) -> usize {
+            self.frames.lock().len()
+        }
+        fn reclaim(&self, target: usize) -> usize {
+            let mut frames = self.frames.lock();
+            let mut freed = 0;
+            while frames.len() > target {
+                raw_dealloc_id(frames.pop().unwrap());
+                freed += 1;
+            }
+            freed
+        }
+    }
+
+    #[test]
+    fn shrink_and_alloc_reclaims_a_registered_shrinker() {
+        // No subsystem in this tree calls `register_shrinker` yet; this
+        // drives the mechanism itself so it isn't untested dead code
+        // while that wiring is pending.
+        let _guard = TEST_ALLOC_LOCK.lock();
+        // Other tests leave frames free in the shared bitmap; start empty.
+        *FRAME_ALLOCATOR.lock() = FrameAlloc::default();
+        FRAME_ALLOCATOR.lock().insert(200..201);
+        TOTAL_FRAMES.store(1, Ordering::Relaxed);
+        FREE_FRAMES.store(1, Ordering::Relaxed);
+        let held = raw_alloc_id().unwrap();
+        assert_eq!(FRAME_ALLOCATOR.lock().alloc(), None, "the only frame should be held by us");
+
+        lazy_static::lazy_static! {
+            static ref SHRINKER: TestShrinker = TestShrinker { frames: SpinNoIrqLock::new(Vec::new()) };
+        }
+        SHRINKER.frames.lock().push(held);
+        register_shrinker(&*SHRINKER);
+
+        assert!(shrink_and_alloc().is_some());
+        assert_eq!(SHRINKER.count(), 0);
+    }
+
+    /// A swap device backed by an in-process `Vec`, standing in for a
+    /// real block-device-backed swap file.
+    struct TestSwapDevice {
+        slots: SpinNoIrqLock<Vec<[u8; PAGE_SIZE]>>,
+    }
+
+    impl SwapDevice for TestSwapDevice {
+        fn write(&self, frame: &[u8]) -> usize {
+            let mut slots = self.slots.lock();
+            let mut slot = [0u8; PAGE_SIZE];
+            slot.copy_from_slice(frame);
+            slots.push(slot);
+            slots.len() - 1
+        }
+        fn read(&self, id: usize, frame: &mut [u8]) {
+            frame.copy_from_slice(&self.slots.lock()[id]);
+        }
+    }
+
+    #[test]
+    fn init_swap_device_installs_a_working_backing_store() {
+        // No arch boot path in this tree calls `init_swap_device` yet;
+        // this drives the read/write contract it installs so it isn't
+        // untested dead code while that wiring is pending.
+        let _guard = TEST_ALLOC_LOCK.lock();
+        init_swap_device(Box::new(TestSwapDevice { slots: SpinNoIrqLock::new(Vec::new()) }));
+        let page = [0x42u8; PAGE_SIZE];
+        let id = SWAP_DEVICE.lock().as_ref().unwrap().write(&page);
+        let mut out = [0u8; PAGE_SIZE];
+        SWAP_DEVICE.lock().as_ref().unwrap().read(id, &mut out);
+        assert_eq!(&out[..], &page[..]);
+    }
+
+    // Exercise the underlying `BitAlloc::alloc_contiguous` directly too,
+    // covering alignment/fragmentation behavior the wrapper inherits.
+
+    #[test]
+    fn alloc_contiguous_respects_alignment() {
+        let mut ba = BitAlloc64K::default();
+        ba.insert(0..64);
+        let base = ba.alloc_contiguous(4, 2).unwrap(); // 4 frames, aligned to 4
+        assert_eq!(base % 4, 0);
+    }
+
+    #[test]
+    fn alloc_contiguous_skips_fragmented_holes() {
+        let mut ba = BitAlloc64K::default();
+        ba.insert(0..4);
+        let frames: Vec<usize> = (0..4).map(|_| ba.alloc().unwrap()).collect();
+        // Free every other frame: 0 and 2 are free but not adjacent.
+        ba.dealloc(frames[0]);
+        ba.dealloc(frames[2]);
+        assert!(ba.alloc_contiguous(2, 0).is_none());
+    }
+
+    #[test]
+    fn dealloc_contiguous_frees_whole_run() {
+        let mut ba = BitAlloc64K::default();
+        ba.insert(0..8);
+        let base = ba.alloc_contiguous(8, 0).unwrap();
+        for frame in base..base + 8 {
+            ba.dealloc(frame);
+        }
+        // The whole range should be available again as one run.
+        assert_eq!(ba.alloc_contiguous(8, 0), Some(base));
+    }
+}